@@ -1,8 +1,10 @@
-use bincode::{deserialize_from, serialize_into};
+use bincode::{deserialize_from, serialize, serialize_into};
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use std::fs::{File, OpenOptions};
-use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpListener;
@@ -38,6 +40,30 @@ impl BTreeIndex {
             .or_insert_with(Vec::new)
             .push(row_index);
     }
+
+    fn remove(&mut self, key: &str, row_index: usize) {
+        if let Some(indices) = self.tree.get_mut(key) {
+            indices.retain(|&i| i != row_index);
+            if indices.is_empty() {
+                self.tree.remove(key);
+            }
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Option<&Vec<usize>> {
+        self.tree.get(key)
+    }
+
+    fn from_table(table: &Table) -> Self {
+        let mut index = BTreeIndex::new();
+        for (row_index, row) in table.rows.iter().enumerate() {
+            for (i, value) in row.iter().enumerate() {
+                let key = format!("{}:{}", table.columns[i].name, value);
+                index.insert(key, row_index);
+            }
+        }
+        index
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -46,84 +72,1021 @@ struct DatabaseState {
     indexes: HashMap<String, BTreeIndex>,
 }
 
-struct Database {
+/// The on-disk schema version this binary writes. Bump this and add an
+/// entry to `migrations()` whenever `DatabaseState` (or a nested type)
+/// changes shape.
+const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Deserialize)]
+struct PersistedState {
+    schema_version: u32,
     state: DatabaseState,
+}
+
+#[derive(Serialize)]
+struct PersistedStateRef<'a> {
+    schema_version: u32,
+    state: &'a DatabaseState,
+}
+
+type Migration = fn(&mut DatabaseState);
+
+/// Ordered list of migrations, keyed by the schema version they upgrade
+/// *to*. Every migration whose version is greater than the version stored
+/// in a loaded file is run, in order, before the state is handed to
+/// `Database`.
+fn migrations() -> Vec<(u32, Migration)> {
+    vec![(1, migrate_to_v1)]
+}
+
+/// Version 0 files predate the `{schema_version, state}` envelope; the
+/// `DatabaseState` shape itself didn't change, so this just lets the
+/// version counter catch up to `CURRENT_SCHEMA_VERSION`.
+fn migrate_to_v1(_state: &mut DatabaseState) {}
+
+fn run_migrations_from(from_version: u32, state: &mut DatabaseState) {
+    for (version, migrate) in migrations() {
+        if version > from_version {
+            migrate(state);
+        }
+    }
+}
+
+/// One already-validated mutation. `Database` appends one of these to the
+/// WAL for every successful CREATE/ALTER/INSERT/UPDATE/DELETE before the
+/// caller's response is formatted, so replaying the log against the last
+/// snapshot always reconstructs the acknowledged state. The append is not
+/// fsynced every time: an append within `wal_fsync_interval` of the last
+/// fsync is acknowledged before it has been fsynced (see `append_wal`).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum WalRecord {
+    CreateTable(Table),
+    AlterTableAddColumn {
+        table: String,
+        column: String,
+        data_type: String,
+    },
+    Insert {
+        table: String,
+        values: Vec<String>,
+    },
+    Update {
+        table: String,
+        column: String,
+        value: String,
+        where_clause: Option<String>,
+    },
+    Delete {
+        table: String,
+        where_clause: Option<String>,
+    },
+}
+
+/// Replays one record against the in-memory table store. Records are only
+/// ever appended once their operation has already been validated, so this
+/// never needs to fail — a record that doesn't apply cleanly (e.g. its
+/// table is missing because a later snapshot already dropped it) is
+/// skipped rather than treated as corruption.
+///
+/// `seq` is this record's position in the replay order (1-based, assigned
+/// by `replay_wal`), stamped onto whichever table the record touches so
+/// `save_to_file` can later tell which WAL records that table's data
+/// reflects — see `TableEntry`.
+fn apply_wal_record(tables: &mut HashMap<String, TableEntry>, seq: u64, record: WalRecord) {
+    match record {
+        WalRecord::CreateTable(table) => {
+            tables
+                .entry(table.name.clone())
+                .or_insert_with(|| Arc::new(RwLock::new((table, BTreeIndex::new(), seq))));
+        }
+        WalRecord::AlterTableAddColumn {
+            table,
+            column,
+            data_type,
+        } => {
+            if let Some(entry) = tables.get(&table) {
+                let mut guard = entry.write().unwrap();
+                let (table, index, applied_seq) = &mut *guard;
+                if !table.columns.iter().any(|c| c.name == column) {
+                    backfill_new_column(table, index, &column, &data_type);
+                }
+                *applied_seq = seq;
+            }
+        }
+        WalRecord::Insert { table, values } => {
+            if let Some(entry) = tables.get(&table) {
+                let mut guard = entry.write().unwrap();
+                let (table, index, applied_seq) = &mut *guard;
+                let row_index = table.rows.len();
+                for (i, value) in values.iter().enumerate() {
+                    let key = format!("{}:{}", table.columns[i].name, value);
+                    index.insert(key, row_index);
+                }
+                table.rows.push(values);
+                *applied_seq = seq;
+            }
+        }
+        WalRecord::Update {
+            table,
+            column,
+            value,
+            where_clause,
+        } => {
+            if let Some(entry) = tables.get(&table) {
+                let mut guard = entry.write().unwrap();
+                let (table, index, applied_seq) = &mut *guard;
+                let row_indices = match &where_clause {
+                    Some(clause) => apply_where_clause(table, index, clause).unwrap_or_default(),
+                    None => (0..table.rows.len()).collect(),
+                };
+                if let Some(column_index) = table.columns.iter().position(|c| c.name == column) {
+                    let mut old_values = Vec::with_capacity(row_indices.len());
+                    for &row_index in &row_indices {
+                        old_values.push(table.rows[row_index][column_index].clone());
+                        table.rows[row_index][column_index] = value.clone();
+                    }
+                    for (&row_index, old_value) in row_indices.iter().zip(old_values.iter()) {
+                        index.remove(&format!("{}:{}", column, old_value), row_index);
+                        index.insert(format!("{}:{}", column, value), row_index);
+                    }
+                }
+                *applied_seq = seq;
+            }
+        }
+        WalRecord::Delete { table, where_clause } => {
+            if let Some(entry) = tables.get(&table) {
+                let mut guard = entry.write().unwrap();
+                let (table, index, applied_seq) = &mut *guard;
+                let mut row_indices = match &where_clause {
+                    Some(clause) => apply_where_clause(table, index, clause).unwrap_or_default(),
+                    None => (0..table.rows.len()).collect(),
+                };
+                row_indices.sort_unstable();
+                row_indices.dedup();
+                for &row_index in row_indices.iter().rev() {
+                    table.rows.remove(row_index);
+                }
+                *index = BTreeIndex::from_table(table);
+                *applied_seq = seq;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum DbError {
+    TableExists(String),
+    TableNotFound(String),
+    ColumnExists(String),
+    ColumnNotFound(String),
+    TypeMismatch(String),
+    SyntaxError(String),
+    ArityMismatch(String),
+}
+
+impl DbError {
+    /// A stable short code analogous to a Postgres SQLSTATE class, so
+    /// clients can branch on the error kind instead of parsing English.
+    fn code(&self) -> &'static str {
+        match self {
+            DbError::TableExists(_) => "42P07",
+            DbError::TableNotFound(_) => "42P01",
+            DbError::ColumnExists(_) => "42701",
+            DbError::ColumnNotFound(_) => "42703",
+            DbError::TypeMismatch(_) => "22P02",
+            DbError::SyntaxError(_) => "42601",
+            DbError::ArityMismatch(_) => "08P01",
+        }
+    }
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            DbError::TableExists(msg) => msg,
+            DbError::TableNotFound(msg) => msg,
+            DbError::ColumnExists(msg) => msg,
+            DbError::ColumnNotFound(msg) => msg,
+            DbError::TypeMismatch(msg) => msg,
+            DbError::SyntaxError(msg) => msg,
+            DbError::ArityMismatch(msg) => msg,
+        };
+        write!(f, "{}", message)
+    }
+}
+
+/// A typed statement result, the structured counterpart to the
+/// pipe-joined text responses this server also speaks. `Select` separates
+/// column names from row data instead of folding the header into the row
+/// list, and `Error` carries the same SQLSTATE-like code `DbError::code`
+/// produces so clients never have to parse English to branch on failure.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum StatementResult {
+    CreateTable,
+    TableAltered,
+    Insert {
+        count: usize,
+    },
+    Select {
+        columns: Vec<String>,
+        rows: Vec<Vec<String>>,
+    },
+    Update {
+        count: usize,
+    },
+    Delete {
+        count: usize,
+    },
+    Prepared,
+    Error {
+        code: String,
+        message: String,
+    },
+}
+
+impl StatementResult {
+    fn from_result(result: Result<StatementResult, DbError>) -> StatementResult {
+        match result {
+            Ok(r) => r,
+            Err(e) => StatementResult::Error {
+                code: e.code().to_string(),
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum PreparedOperation {
+    Select {
+        columns: Vec<String>,
+        where_template: Option<String>,
+    },
+    Insert {
+        values_template: Vec<String>,
+    },
+    Update {
+        column: String,
+        value_template: String,
+        where_template: Option<String>,
+    },
+    Delete {
+        where_template: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct PreparedStatement {
+    table: String,
+    operation: PreparedOperation,
+    param_count: usize,
+}
+
+impl PreparedStatement {
+    fn parse(stmt_parts: &[&str]) -> Result<Self, DbError> {
+        if stmt_parts.is_empty() {
+            return Err(DbError::SyntaxError("Empty prepared statement".to_string()));
+        }
+
+        let (table, operation) = match stmt_parts[0].to_uppercase().as_str() {
+            "SELECT" => {
+                if stmt_parts.len() < 4 || !stmt_parts.contains(&"FROM") {
+                    return Err(DbError::SyntaxError("Invalid SELECT syntax".to_string()));
+                }
+                let from_position = stmt_parts
+                    .iter()
+                    .position(|&p| p.to_uppercase() == "FROM")
+                    .unwrap();
+                let table = stmt_parts[from_position + 1].to_string();
+                let where_position = stmt_parts.iter().position(|&p| p.to_uppercase() == "WHERE");
+
+                let columns: Vec<String> = if stmt_parts[1] == "*" {
+                    vec!["*".to_string()]
+                } else {
+                    stmt_parts[1..from_position]
+                        .iter()
+                        .filter(|&&c| c != ",")
+                        .map(|&s| s.to_string())
+                        .collect()
+                };
+
+                let where_template = where_position.map(|pos| stmt_parts[pos + 1..].join(" "));
+                (
+                    table,
+                    PreparedOperation::Select {
+                        columns,
+                        where_template,
+                    },
+                )
+            }
+            "INSERT" => {
+                if stmt_parts.len() < 5
+                    || stmt_parts[1].to_uppercase() != "INTO"
+                    || stmt_parts[3].to_uppercase() != "VALUES"
+                {
+                    return Err(DbError::SyntaxError("Invalid INSERT syntax".to_string()));
+                }
+                let table = stmt_parts[2].to_string();
+                let values_template: Vec<String> =
+                    stmt_parts[4..].iter().map(|s| s.to_string()).collect();
+                (table, PreparedOperation::Insert { values_template })
+            }
+            "UPDATE" => {
+                if stmt_parts.len() < 6
+                    || stmt_parts[2].to_uppercase() != "SET"
+                    || stmt_parts[4] != "="
+                {
+                    return Err(DbError::SyntaxError("Invalid UPDATE syntax".to_string()));
+                }
+                let table = stmt_parts[1].to_string();
+                let column = stmt_parts[3].to_string();
+                let where_position = stmt_parts.iter().position(|&p| p.to_uppercase() == "WHERE");
+                let value_template = match where_position {
+                    Some(pos) => stmt_parts[5..pos].join(" "),
+                    None => stmt_parts[5..].join(" "),
+                };
+                let where_template = where_position.map(|pos| stmt_parts[pos + 1..].join(" "));
+                (
+                    table,
+                    PreparedOperation::Update {
+                        column,
+                        value_template,
+                        where_template,
+                    },
+                )
+            }
+            "DELETE" => {
+                if stmt_parts.len() < 3 || stmt_parts[1].to_uppercase() != "FROM" {
+                    return Err(DbError::SyntaxError("Invalid DELETE syntax".to_string()));
+                }
+                let table = stmt_parts[2].to_string();
+                let where_position = stmt_parts.iter().position(|&p| p.to_uppercase() == "WHERE");
+                let where_template = where_position.map(|pos| stmt_parts[pos + 1..].join(" "));
+                (table, PreparedOperation::Delete { where_template })
+            }
+            other => {
+                return Err(DbError::SyntaxError(format!(
+                    "Cannot prepare statement of type '{}'",
+                    other
+                )))
+            }
+        };
+
+        let param_count = max_placeholder_index(&stmt_parts.join(" "));
+
+        Ok(PreparedStatement {
+            table,
+            operation,
+            param_count,
+        })
+    }
+}
+
+fn max_placeholder_index(text: &str) -> usize {
+    text.split_whitespace()
+        .filter_map(|tok| tok.strip_prefix('$'))
+        .filter_map(|n| n.parse::<usize>().ok())
+        .max()
+        .unwrap_or(0)
+}
+
+fn substitute_placeholders(template: &str, args: &[String]) -> String {
+    template
+        .split_whitespace()
+        .map(|tok| match tok.strip_prefix('$').and_then(|n| n.parse::<usize>().ok()) {
+            Some(n) if n >= 1 && n <= args.len() => args[n - 1].clone(),
+            _ => tok.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// The backfill value for a column added by `ALTER TABLE ... ADD COLUMN`.
+/// Has to parse under its own `data_type`, or every pre-existing row fails
+/// any later range/inequality `WHERE` on that column the moment
+/// `compare_cells` tries to parse the backfilled cell.
+fn default_value_for_type(data_type: &str) -> String {
+    match data_type.to_uppercase().as_str() {
+        "INTEGER" => "0".to_string(),
+        "FLOAT" => "0.0".to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Adds `column_name` to `table` and backfills every existing row with its
+/// typed default, indexing each backfilled cell along the way. Shared by the
+/// live `ALTER TABLE ADD COLUMN` path and WAL replay so the two can never
+/// drift apart on how a new column gets backfilled.
+fn backfill_new_column(table: &mut Table, index: &mut BTreeIndex, column_name: &str, data_type: &str) {
+    table.columns.push(Column {
+        name: column_name.to_string(),
+        data_type: data_type.to_string(),
+    });
+    let default_value = default_value_for_type(data_type);
+    for (row_index, row) in table.rows.iter_mut().enumerate() {
+        row.push(default_value.clone());
+        let key = format!("{}:{}", column_name, default_value);
+        index.insert(key, row_index);
+    }
+}
+
+fn validate_arg_type(column: &Column, value: &str) -> Result<(), DbError> {
+    match column.data_type.to_uppercase().as_str() {
+        "INTEGER" => value.parse::<i64>().map(|_| ()).map_err(|_| {
+            DbError::TypeMismatch(format!(
+                "Argument '{}' is not a valid INTEGER for column '{}'",
+                value, column.name
+            ))
+        }),
+        "FLOAT" => value.parse::<f64>().map(|_| ()).map_err(|_| {
+            DbError::TypeMismatch(format!(
+                "Argument '{}' is not a valid FLOAT for column '{}'",
+                value, column.name
+            ))
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn validate_where_arg_types(columns: &[Column], clause: &str) -> Result<(), DbError> {
+    let parts: Vec<&str> = clause.split_whitespace().collect();
+    if parts.len() != 3 {
+        return Ok(());
+    }
+    if let Some(column) = columns.iter().find(|c| c.name == parts[0]) {
+        validate_arg_type(column, parts[2])?;
+    }
+    Ok(())
+}
+
+fn execute_prepared(
+    prepared: &PreparedStatement,
+    args: &[String],
+    db: &Database,
+) -> Result<StatementResult, DbError> {
+    let table_columns = db.table_columns(&prepared.table)?;
+
+    match &prepared.operation {
+        PreparedOperation::Select {
+            columns,
+            where_template,
+        } => {
+            let where_clause = where_template
+                .as_ref()
+                .map(|t| substitute_placeholders(t, args));
+            if let Some(clause) = &where_clause {
+                validate_where_arg_types(&table_columns, clause)?;
+            }
+            let mut results =
+                db.select(&prepared.table, columns.clone(), where_clause.as_deref())?;
+            let columns = if results.is_empty() {
+                Vec::new()
+            } else {
+                results.remove(0)
+            };
+            Ok(StatementResult::Select {
+                columns,
+                rows: results,
+            })
+        }
+        PreparedOperation::Insert { values_template } => {
+            let mut values = Vec::with_capacity(values_template.len());
+            for (i, value_template) in values_template.iter().enumerate() {
+                let value = substitute_placeholders(value_template, args);
+                if value_template.starts_with('$') {
+                    if let Some(column) = table_columns.get(i) {
+                        validate_arg_type(column, &value)?;
+                    }
+                }
+                values.push(value);
+            }
+            db.insert(&prepared.table, values)?;
+            Ok(StatementResult::Insert { count: 1 })
+        }
+        PreparedOperation::Update {
+            column,
+            value_template,
+            where_template,
+        } => {
+            let value = substitute_placeholders(value_template, args);
+            if value_template.starts_with('$') {
+                if let Some(col) = table_columns.iter().find(|c| &c.name == column) {
+                    validate_arg_type(col, &value)?;
+                }
+            }
+            let where_clause = where_template
+                .as_ref()
+                .map(|t| substitute_placeholders(t, args));
+            if let Some(clause) = &where_clause {
+                validate_where_arg_types(&table_columns, clause)?;
+            }
+            let count = db.update(&prepared.table, column, &value, where_clause.as_deref())?;
+            Ok(StatementResult::Update { count })
+        }
+        PreparedOperation::Delete { where_template } => {
+            let where_clause = where_template
+                .as_ref()
+                .map(|t| substitute_placeholders(t, args));
+            if let Some(clause) = &where_clause {
+                validate_where_arg_types(&table_columns, clause)?;
+            }
+            let count = db.delete(&prepared.table, where_clause.as_deref())?;
+            Ok(StatementResult::Delete { count })
+        }
+    }
+}
+
+fn apply_where_clause(
+    table: &Table,
+    index: &BTreeIndex,
+    where_clause: &str,
+) -> Result<Vec<usize>, DbError> {
+    let parts: Vec<&str> = where_clause.split_whitespace().collect();
+    if parts.len() != 3 {
+        return Err(DbError::SyntaxError(
+            "Invalid WHERE clause syntax".to_string(),
+        ));
+    }
+
+    let column = parts[0];
+    let operator = parts[1];
+    let value = parts[2];
+
+    let column_index = table
+        .columns
+        .iter()
+        .position(|c| c.name == column)
+        .ok_or_else(|| DbError::ColumnNotFound(format!("Column '{}' not found", column)))?;
+
+    if operator == "=" {
+        let key = format!("{}:{}", column, value);
+        let mut result = index.lookup(&key).cloned().unwrap_or_default();
+        result.sort_unstable();
+        return Ok(result);
+    }
+
+    let data_type = table.columns[column_index].data_type.to_uppercase();
+
+    let mut result = Vec::new();
+    for (i, row) in table.rows.iter().enumerate() {
+        if compare_cells(&row[column_index], value, operator, &data_type)? {
+            result.push(i);
+        }
+    }
+    Ok(result)
+}
+
+fn compare_cells(cell: &str, literal: &str, operator: &str, data_type: &str) -> Result<bool, DbError> {
+    let ordering = match data_type {
+        "INTEGER" => {
+            let cell_val: i64 = cell.parse().map_err(|_| {
+                DbError::TypeMismatch(format!("Cannot parse '{}' as INTEGER", cell))
+            })?;
+            let literal_val: i64 = literal.parse().map_err(|_| {
+                DbError::TypeMismatch(format!("Cannot parse '{}' as INTEGER", literal))
+            })?;
+            cell_val.cmp(&literal_val)
+        }
+        "FLOAT" => {
+            let cell_val: f64 = cell.parse().map_err(|_| {
+                DbError::TypeMismatch(format!("Cannot parse '{}' as FLOAT", cell))
+            })?;
+            let literal_val: f64 = literal.parse().map_err(|_| {
+                DbError::TypeMismatch(format!("Cannot parse '{}' as FLOAT", literal))
+            })?;
+            cell_val
+                .partial_cmp(&literal_val)
+                .ok_or_else(|| DbError::TypeMismatch("Cannot compare NaN values".to_string()))?
+        }
+        _ => cell.cmp(literal),
+    };
+
+    match operator {
+        "=" => Ok(ordering == std::cmp::Ordering::Equal),
+        "!=" => Ok(ordering != std::cmp::Ordering::Equal),
+        ">" => Ok(ordering == std::cmp::Ordering::Greater),
+        "<" => Ok(ordering == std::cmp::Ordering::Less),
+        ">=" => Ok(ordering != std::cmp::Ordering::Less),
+        "<=" => Ok(ordering != std::cmp::Ordering::Greater),
+        _ => Err(DbError::SyntaxError(format!(
+            "Unsupported operator: {}",
+            operator
+        ))),
+    }
+}
+
+/// A table's rows and its secondary index, locked as a unit so a writer
+/// can never observe one without the other, plus the `wal_seq` (see
+/// `Database::wal_seq`) of the last WAL record this table's data reflects.
+/// `save_to_file` takes the minimum of that seq across every table to find
+/// a WAL prefix it can safely drop — see `save_to_file` for why.
+type TableEntry = Arc<RwLock<(Table, BTreeIndex, u64)>>;
+
+struct Database {
+    /// One `RwLock` per table, held under a lighter top-level `RwLock`
+    /// that's only ever write-locked to add a table. This lets concurrent
+    /// SELECTs on a table share a read lock, concurrent writers on
+    /// *different* tables proceed fully in parallel, and table creation
+    /// avoid blocking on any table's data.
+    tables: RwLock<HashMap<String, TableEntry>>,
+    prepared: Mutex<HashMap<String, PreparedStatement>>,
     file_path: String,
-    dirty: bool,
-    last_save: Instant,
+    /// Path of the append-only WAL sitting alongside `file_path` (same
+    /// stem, `.wal` extension). Its open handle is `wal`, which is `None`
+    /// once the handle has failed to open, meaning this process runs
+    /// without durability until the next checkpoint manages to reopen it.
+    wal_path: String,
+    wal: Mutex<Option<File>>,
+    /// Monotonically increasing counter, bumped once per successful WAL
+    /// append (see `append_wal_locked`) and never reset for the life of the
+    /// process. Each table's entry records the value this counter held
+    /// right after the last record that touched it, or right after the
+    /// last record `replay_wal` applied if that is later (see `TableEntry`), so
+    /// `save_to_file` can tell exactly which WAL records every table's
+    /// current data already reflects.
+    wal_seq: AtomicU64,
+    /// The `wal_seq` of the oldest record still physically present in the
+    /// WAL file on disk. Since individual records don't carry their own
+    /// seq on disk, this is what lets a truncation figure out how many
+    /// leading records to drop: the Nth record in the file is seq
+    /// `wal_base_seq + N`. Updated by `truncate_wal_up_to_locked`.
+    wal_base_seq: AtomicU64,
+    /// Last time an append fsynced the WAL, and the minimum gap before the
+    /// next append will do so again — see `append_wal_locked`. Every
+    /// append still lands in the file (and in `wal_seq`) immediately; only
+    /// the fsync itself is debounced, the same trade-off `max_dirty_duration`
+    /// already makes for full checkpoints, just on a much shorter fuse.
+    last_wal_fsync: Mutex<Instant>,
+    wal_fsync_interval: Duration,
+    dirty: AtomicBool,
+    last_save: Mutex<Instant>,
     max_dirty_duration: Duration,
 }
 
 impl Database {
     fn new(file_path: &str) -> Self {
+        let wal_path = Path::new(file_path)
+            .with_extension("wal")
+            .to_string_lossy()
+            .into_owned();
         let mut db = Database {
-            state: DatabaseState {
-                tables: HashMap::new(),
-                indexes: HashMap::new(),
-            },
+            tables: RwLock::new(HashMap::new()),
+            prepared: Mutex::new(HashMap::new()),
             file_path: file_path.to_string(),
-            dirty: false,
-            last_save: Instant::now(),
+            wal_path,
+            wal: Mutex::new(None),
+            wal_seq: AtomicU64::new(0),
+            wal_base_seq: AtomicU64::new(0),
+            last_wal_fsync: Mutex::new(Instant::now()),
+            wal_fsync_interval: Duration::from_millis(5),
+            dirty: AtomicBool::new(false),
+            last_save: Mutex::new(Instant::now()),
             max_dirty_duration: Duration::from_secs(5),
         };
         db.load_from_file();
+        db.replay_wal();
+        // Folds the replayed WAL into a fresh snapshot and truncates the
+        // log, so this process always starts from a checkpoint that's
+        // consistent with what it just recovered.
+        db.save_to_file();
         db
     }
 
+    /// Replays every record still in the WAL on top of the tables loaded
+    /// from the last snapshot, recovering any mutation that was
+    /// acknowledged to a client but never made it into a full rewrite.
+    fn replay_wal(&self) {
+        let file = match File::open(&self.wal_path) {
+            Ok(file) => file,
+            Err(_) => return,
+        };
+        let mut reader = file;
+        let mut tables = self.tables.write().unwrap();
+        let mut seq = 0u64;
+        while let Ok(record) = deserialize_from::<_, WalRecord>(&mut reader) {
+            seq += 1;
+            apply_wal_record(&mut tables, seq, record);
+        }
+        // Every table now reflects every replayed record, so the startup
+        // checkpoint in `new` can drop all of them.
+        for entry in tables.values() {
+            entry.write().unwrap().2 = seq;
+        }
+        self.wal_seq.store(seq, Ordering::SeqCst);
+    }
+
+    /// Appends `record` to the WAL, under the WAL mutex for just the
+    /// append itself. Every mutating `Database` method calls this after
+    /// validating the operation but before touching in-memory state —
+    /// still under the table lock it's about to mutate under, so the
+    /// record is in the WAL file before the success response reaches the
+    /// client — but the WAL mutex itself is released the moment the append
+    /// returns, never held across the in-memory mutation or a checkpoint.
+    /// Writers on different tables only contend on this one critical
+    /// section, not on each other's table locks. A writer racing a
+    /// checkpoint can also wait for the snapshot's read lock on its own
+    /// table and for `truncate_wal_up_to_locked`, which holds the WAL
+    /// mutex while it rewrites the WAL file, but never for the write of
+    /// the snapshot file itself.
+    ///
+    /// The fsync that makes the write crash-durable is debounced (see
+    /// `wal_fsync_interval`) rather than done on every single append, so
+    /// a burst of writes pays for one fsync instead of one each. An append
+    /// that skips the fsync is not crash-durable until a later append or
+    /// checkpoint fsyncs the file.
+    ///
+    /// Returns the `wal_seq` now assigned to `record` — callers stamp this
+    /// onto the table they're about to mutate (still under that table's
+    /// write lock) so `save_to_file` can later tell which records that
+    /// table's data reflects. If the WAL isn't writable right now, returns
+    /// the unchanged counter: there's no record on disk to account for.
+    fn append_wal(&self, record: &WalRecord) -> u64 {
+        let mut guard = self.wal.lock().unwrap();
+        self.append_wal_locked(&mut guard, record)
+    }
+
+    fn append_wal_locked(&self, guard: &mut Option<File>, record: &WalRecord) -> u64 {
+        let file = match guard.as_mut() {
+            Some(file) => file,
+            None => return self.wal_seq.load(Ordering::SeqCst),
+        };
+        if let Err(e) = serialize_into(&mut *file, record) {
+            eprintln!("Error appending to WAL: {}", e);
+            return self.wal_seq.load(Ordering::SeqCst);
+        }
+        let mut last_fsync = self.last_wal_fsync.lock().unwrap();
+        if last_fsync.elapsed() >= self.wal_fsync_interval {
+            // The record is already in the file either way, so it still
+            // takes a seq; only the fsync timestamp is left alone.
+            if let Err(e) = file.sync_all() {
+                eprintln!("Error fsyncing WAL: {}", e);
+            } else {
+                *last_fsync = Instant::now();
+            }
+        }
+        self.wal_seq.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Rewrites the WAL file to drop every record with `wal_seq <=
+    /// safe_seq`, keeping any record appended after the snapshot that
+    /// computed `safe_seq` already captured. Records don't carry their own
+    /// seq on disk, so this re-reads the file from the start, skips the
+    /// `safe_seq - wal_base_seq` oldest records (which `wal_base_seq`
+    /// makes it possible to count), and rewrites the rest.
+    ///
+    /// Only skips the rewrite entirely when `guard` already holds an open
+    /// file and there's nothing new to drop — the very first checkpoint
+    /// after construction has `safe_seq == wal_base_seq == 0` but still
+    /// needs this to run once, or the WAL is never actually opened and
+    /// every later append silently does nothing (see `append_wal_locked`).
+    fn truncate_wal_up_to_locked(&self, guard: &mut Option<File>, safe_seq: u64) {
+        let base = self.wal_base_seq.load(Ordering::SeqCst);
+        if guard.is_some() && safe_seq <= base {
+            return;
+        }
+        let skip = safe_seq.saturating_sub(base) as usize;
+
+        let mut remaining = Vec::new();
+        if let Ok(mut reader) = File::open(&self.wal_path) {
+            let mut index = 0usize;
+            while let Ok(record) = deserialize_from::<_, WalRecord>(&mut reader) {
+                if index >= skip {
+                    remaining.push(record);
+                }
+                index += 1;
+            }
+        }
+
+        match OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&self.wal_path)
+        {
+            Ok(mut file) => {
+                for record in &remaining {
+                    if let Err(e) = serialize_into(&mut file, record) {
+                        eprintln!("Error rewriting WAL: {}", e);
+                    }
+                }
+                if let Err(e) = file.sync_all() {
+                    eprintln!("Error fsyncing rewritten WAL: {}", e);
+                }
+                *guard = Some(file);
+                self.wal_base_seq.store(safe_seq, Ordering::SeqCst);
+            }
+            Err(e) => {
+                eprintln!("Error truncating WAL: {}", e);
+                *guard = None;
+            }
+        }
+    }
+
+    /// Splits a freshly loaded/migrated `DatabaseState` into the
+    /// per-table-locked layout `Database` actually runs on. Every table
+    /// starts at `wal_seq` 0 — nothing's been replayed onto it yet, so it
+    /// reflects none of the WAL `replay_wal` is about to apply.
+    fn into_table_store(state: DatabaseState) -> HashMap<String, TableEntry> {
+        let DatabaseState {
+            tables,
+            mut indexes,
+        } = state;
+        tables
+            .into_iter()
+            .map(|(name, table)| {
+                let index = indexes.remove(&name).unwrap_or_else(BTreeIndex::new);
+                (name, Arc::new(RwLock::new((table, index, 0))))
+            })
+            .collect()
+    }
+
     fn load_from_file(&mut self) {
         if let Ok(file) = File::open(&self.file_path) {
-            match deserialize_from(file) {
-                Ok(state) => self.state = state,
-                Err(e) => eprintln!("Error loading database: {}", e),
+            match deserialize_from::<_, PersistedState>(file) {
+                Ok(mut persisted) => {
+                    run_migrations_from(persisted.schema_version, &mut persisted.state);
+                    self.tables = RwLock::new(Self::into_table_store(persisted.state));
+                }
+                Err(_) => {
+                    // Not the envelope format — fall back to a bare
+                    // DatabaseState, the layout every file had before schema
+                    // versioning existed.
+                    if let Ok(file) = File::open(&self.file_path) {
+                        match deserialize_from::<_, DatabaseState>(file) {
+                            Ok(mut state) => {
+                                run_migrations_from(0, &mut state);
+                                self.tables = RwLock::new(Self::into_table_store(state));
+                            }
+                            Err(e) => eprintln!("Error loading database: {}", e),
+                        }
+                    }
+                }
             }
         }
     }
 
+    /// Copies every table and index out from under their individual locks
+    /// into a plain `DatabaseState`, so serialization never has to hold a
+    /// table lock for the duration of the write to disk. Also returns the
+    /// minimum `wal_seq` stamped on any table, i.e. the newest point every
+    /// table in the snapshot is guaranteed to reflect — see `save_to_file`.
+    ///
+    /// Holding `self.tables` read-locked for the whole pass is what makes
+    /// that minimum trustworthy: it blocks `create_table` (which needs the
+    /// write lock) for as long as the snapshot runs, so a table can never
+    /// be observed mid-insert into the map, and each per-table read lock
+    /// below blocks until that table's own writer has finished mutating
+    /// and stamping its seq — never mid-write.
+    fn snapshot(&self) -> (DatabaseState, u64) {
+        let tables = self.tables.read().unwrap();
+        let mut table_map = HashMap::with_capacity(tables.len());
+        let mut index_map = HashMap::with_capacity(tables.len());
+        let mut safe_seq = None;
+        for (name, entry) in tables.iter() {
+            let guard = entry.read().unwrap();
+            table_map.insert(name.clone(), guard.0.clone());
+            index_map.insert(name.clone(), guard.1.clone());
+            safe_seq = Some(safe_seq.map_or(guard.2, |min: u64| min.min(guard.2)));
+        }
+        // No tables means no Insert/Update/Delete/Alter record could exist
+        // (and a concurrent CreateTable is blocked by the read lock we're
+        // still holding), so every record currently in the WAL is safe to
+        // drop — the current counter is as good a floor as any.
+        let safe_seq = safe_seq.unwrap_or_else(|| self.wal_seq.load(Ordering::SeqCst));
+        (
+            DatabaseState {
+                tables: table_map,
+                indexes: index_map,
+            },
+            safe_seq,
+        )
+    }
+
+    /// Checkpoints to disk: snapshot every table, write it out, then
+    /// truncate the WAL up to the point that snapshot actually reflects.
+    /// `snapshot` returns, alongside the `DatabaseState`, the minimum
+    /// `wal_seq` stamped across every table — the newest record every
+    /// table in the snapshot is guaranteed to already include. Records
+    /// past that point (a write that landed on some other table after
+    /// this table's data was copied, or while the snapshot was still in
+    /// progress) stay in the WAL; anything at or before it is redundant
+    /// with what was just written to `file_path` and gets dropped. This
+    /// is what keeps the WAL mutex scoped to each individual append (see
+    /// `append_wal`) instead of needing to be held across the whole
+    /// snapshot-capture -> write -> truncate sequence: the seq recorded
+    /// under each table's own lock stands in for that.
     fn save_to_file(&self) {
+        let (state, safe_seq) = self.snapshot();
         if let Ok(file) = OpenOptions::new()
             .write(true)
             .truncate(true)
             .create(true)
             .open(&self.file_path)
         {
-            if let Err(e) = serialize_into(file, &self.state) {
+            let persisted = PersistedStateRef {
+                schema_version: CURRENT_SCHEMA_VERSION,
+                state: &state,
+            };
+            if let Err(e) = serialize_into(file, &persisted) {
                 eprintln!("Error saving database: {}", e);
+                return;
             }
+            let mut wal_guard = self.wal.lock().unwrap();
+            self.truncate_wal_up_to_locked(&mut wal_guard, safe_seq);
         }
     }
 
-    fn create_table(&mut self, table: Table) -> Result<(), String> {
-        if self.state.tables.contains_key(&table.name) {
-            return Err(format!("Table '{}' already exists", table.name));
+    /// Looks up the `Arc` for a table without holding any lock past the
+    /// lookup itself, so callers can then take exactly the read or write
+    /// lock their operation needs.
+    fn table_entry(&self, table_name: &str) -> Result<TableEntry, DbError> {
+        self.tables
+            .read()
+            .unwrap()
+            .get(table_name)
+            .cloned()
+            .ok_or_else(|| DbError::TableNotFound(format!("Table '{}' not found", table_name)))
+    }
+
+    fn table_columns(&self, table_name: &str) -> Result<Vec<Column>, DbError> {
+        let entry = self.table_entry(table_name)?;
+        let guard = entry.read().unwrap();
+        Ok(guard.0.columns.clone())
+    }
+
+    fn create_table(&self, table: Table) -> Result<(), DbError> {
+        let mut tables = self.tables.write().unwrap();
+        if tables.contains_key(&table.name) {
+            return Err(DbError::TableExists(format!(
+                "Table '{}' already exists",
+                table.name
+            )));
         }
+        let seq = self.append_wal(&WalRecord::CreateTable(table.clone()));
         let table_name = table.name.clone();
-        self.state.tables.insert(table_name.clone(), table.clone());
-        self.state.indexes.insert(table_name, BTreeIndex::new());
+        tables.insert(table_name, Arc::new(RwLock::new((table, BTreeIndex::new(), seq))));
+        drop(tables);
         self.save_to_file();
         Ok(())
     }
 
-    fn insert(&mut self, table_name: &str, values: Vec<String>) -> Result<(), String> {
-        if let Some(table) = self.state.tables.get_mut(table_name) {
-            if values.len() != table.columns.len() {
-                return Err("Number of values doesn't match number of columns".to_string());
-            }
-            let row_index = table.rows.len();
-            table.rows.push(values.clone());
+    fn alter_table_add_column(
+        &self,
+        table_name: &str,
+        column_name: &str,
+        data_type: &str,
+    ) -> Result<(), DbError> {
+        let entry = self.table_entry(table_name)?;
+        let mut guard = entry.write().unwrap();
+        let (table, index, applied_seq) = &mut *guard;
 
-            if let Some(index) = self.state.indexes.get_mut(table_name) {
-                for (i, value) in values.iter().enumerate() {
-                    let key = format!("{}:{}", table.columns[i].name, value);
-                    index.insert(key, row_index);
-                }
-            }
+        if table.columns.iter().any(|c| c.name == column_name) {
+            return Err(DbError::ColumnExists(format!(
+                "Column '{}' already exists",
+                column_name
+            )));
+        }
 
-            self.dirty = true;
-            self.save_if_needed();
-            Ok(())
-        } else {
-            Err(format!("Table '{}' not found", table_name))
+        let seq = self.append_wal(&WalRecord::AlterTableAddColumn {
+            table: table_name.to_string(),
+            column: column_name.to_string(),
+            data_type: data_type.to_string(),
+        });
+        backfill_new_column(table, index, column_name, data_type);
+        *applied_seq = seq;
+        drop(guard);
+
+        self.mark_dirty();
+        self.save_if_needed();
+        Ok(())
+    }
+
+    fn insert(&self, table_name: &str, values: Vec<String>) -> Result<(), DbError> {
+        let entry = self.table_entry(table_name)?;
+        let mut guard = entry.write().unwrap();
+        let (table, index, applied_seq) = &mut *guard;
+
+        if values.len() != table.columns.len() {
+            return Err(DbError::ArityMismatch(
+                "Number of values doesn't match number of columns".to_string(),
+            ));
+        }
+        let seq = self.append_wal(&WalRecord::Insert {
+            table: table_name.to_string(),
+            values: values.clone(),
+        });
+        let row_index = table.rows.len();
+        table.rows.push(values.clone());
+        for (i, value) in values.iter().enumerate() {
+            let key = format!("{}:{}", table.columns[i].name, value);
+            index.insert(key, row_index);
         }
+        *applied_seq = seq;
+        drop(guard);
+
+        self.mark_dirty();
+        self.save_if_needed();
+        Ok(())
     }
 
     fn select(
@@ -131,93 +1094,162 @@ impl Database {
         table_name: &str,
         columns: Vec<String>,
         where_clause: Option<&str>,
-    ) -> Result<Vec<Vec<String>>, String> {
-        if let Some(table) = self.state.tables.get(table_name) {
-            let column_indices: Vec<usize> = if columns.len() == 1 && columns[0] == "*" {
-                (0..table.columns.len()).collect()
-            } else {
-                columns
-                    .iter()
-                    .map(|col| table.columns.iter().position(|c| c.name == *col))
-                    .collect::<Option<Vec<usize>>>()
-                    .ok_or_else(|| "One or more columns not found".to_string())?
-            };
+    ) -> Result<Vec<Vec<String>>, DbError> {
+        let entry = self.table_entry(table_name)?;
+        let guard = entry.read().unwrap();
+        let table = &guard.0;
+        let index = &guard.1;
 
-            let mut result = Vec::new();
-            let header: Vec<String> = column_indices
+        let column_indices: Vec<usize> = if columns.len() == 1 && columns[0] == "*" {
+            (0..table.columns.len()).collect()
+        } else {
+            columns
                 .iter()
-                .map(|&i| table.columns[i].name.clone())
-                .collect();
-            result.push(header);
-
-            let rows_to_process = if let Some(where_clause) = where_clause {
-                self.apply_where_clause(table_name, where_clause)?
-            } else {
-                (0..table.rows.len()).collect()
-            };
+                .map(|col| table.columns.iter().position(|c| c.name == *col))
+                .collect::<Option<Vec<usize>>>()
+                .ok_or_else(|| {
+                    DbError::ColumnNotFound("One or more columns not found".to_string())
+                })?
+        };
 
-            for row_index in rows_to_process {
-                let row = &table.rows[row_index];
-                let selected_values: Vec<String> =
-                    column_indices.iter().map(|&i| row[i].clone()).collect();
-                result.push(selected_values);
-            }
+        let mut result = Vec::new();
+        let header: Vec<String> = column_indices
+            .iter()
+            .map(|&i| table.columns[i].name.clone())
+            .collect();
+        result.push(header);
 
-            Ok(result)
+        let rows_to_process = if let Some(where_clause) = where_clause {
+            apply_where_clause(table, index, where_clause)?
         } else {
-            Err(format!("Table '{}' not found", table_name))
+            (0..table.rows.len()).collect()
+        };
+
+        for row_index in rows_to_process {
+            let row = &table.rows[row_index];
+            let selected_values: Vec<String> =
+                column_indices.iter().map(|&i| row[i].clone()).collect();
+            result.push(selected_values);
         }
+
+        Ok(result)
     }
 
-    fn apply_where_clause(
+    fn update(
         &self,
         table_name: &str,
-        where_clause: &str,
-    ) -> Result<Vec<usize>, String> {
-        let parts: Vec<&str> = where_clause.split_whitespace().collect();
-        if parts.len() != 3 {
-            return Err("Invalid WHERE clause syntax".to_string());
+        column: &str,
+        value: &str,
+        where_clause: Option<&str>,
+    ) -> Result<usize, DbError> {
+        let entry = self.table_entry(table_name)?;
+        let mut guard = entry.write().unwrap();
+        let (table, index, applied_seq) = &mut *guard;
+
+        let row_indices = match where_clause {
+            Some(clause) => apply_where_clause(table, index, clause)?,
+            None => (0..table.rows.len()).collect(),
+        };
+
+        let column_index = table
+            .columns
+            .iter()
+            .position(|c| c.name == column)
+            .ok_or_else(|| DbError::ColumnNotFound(format!("Column '{}' not found", column)))?;
+
+        let seq = self.append_wal(&WalRecord::Update {
+            table: table_name.to_string(),
+            column: column.to_string(),
+            value: value.to_string(),
+            where_clause: where_clause.map(|s| s.to_string()),
+        });
+
+        let mut old_values = Vec::with_capacity(row_indices.len());
+        for &row_index in &row_indices {
+            old_values.push(table.rows[row_index][column_index].clone());
+            table.rows[row_index][column_index] = value.to_string();
         }
 
-        let column = parts[0];
-        let operator = parts[1];
-        let value = parts[2];
+        for (&row_index, old_value) in row_indices.iter().zip(old_values.iter()) {
+            let old_key = format!("{}:{}", column, old_value);
+            index.remove(&old_key, row_index);
+            let new_key = format!("{}:{}", column, value);
+            index.insert(new_key, row_index);
+        }
+        *applied_seq = seq;
+        drop(guard);
 
-        if let Some(table) = self.state.tables.get(table_name) {
-            let column_index = table
-                .columns
-                .iter()
-                .position(|c| c.name == column)
-                .ok_or_else(|| format!("Column '{}' not found", column))?;
-
-            match operator {
-                "=" => {
-                    let mut result = Vec::new();
-                    for (i, row) in table.rows.iter().enumerate() {
-                        if row[column_index] == value {
-                            result.push(i);
-                        }
-                    }
-                    Ok(result)
-                }
-                // Add support for other operators like >, <, >=, <= if needed
-                _ => Err(format!("Unsupported operator: {}", operator)),
-            }
-        } else {
-            Err(format!("Table '{}' not found", table_name))
+        self.mark_dirty();
+        self.save_if_needed();
+        Ok(row_indices.len())
+    }
+
+    fn delete(&self, table_name: &str, where_clause: Option<&str>) -> Result<usize, DbError> {
+        let entry = self.table_entry(table_name)?;
+        let mut guard = entry.write().unwrap();
+        let (table, index, applied_seq) = &mut *guard;
+
+        let mut row_indices = match where_clause {
+            Some(clause) => apply_where_clause(table, index, clause)?,
+            None => (0..table.rows.len()).collect(),
+        };
+        row_indices.sort_unstable();
+        row_indices.dedup();
+
+        let seq = self.append_wal(&WalRecord::Delete {
+            table: table_name.to_string(),
+            where_clause: where_clause.map(|s| s.to_string()),
+        });
+
+        let deleted_count = row_indices.len();
+        for &row_index in row_indices.iter().rev() {
+            table.rows.remove(row_index);
         }
+        *index = BTreeIndex::from_table(table);
+        *applied_seq = seq;
+        drop(guard);
+
+        self.mark_dirty();
+        self.save_if_needed();
+        Ok(deleted_count)
+    }
+
+    fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
     }
 
-    fn save_if_needed(&mut self) {
-        if self.dirty && self.last_save.elapsed() >= self.max_dirty_duration {
+    fn save_if_needed(&self) {
+        if !self.dirty.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut last_save = self.last_save.lock().unwrap();
+        if last_save.elapsed() >= self.max_dirty_duration {
             self.save_to_file();
-            self.dirty = false;
-            self.last_save = Instant::now();
+            self.dirty.store(false, Ordering::Relaxed);
+            *last_save = Instant::now();
         }
     }
 }
 
-async fn handle_client(mut stream: tokio::net::TcpStream, db: Arc<Mutex<Database>>) {
+/// Which wire protocol a connection speaks. `Text` is the original
+/// pipe-joined-string protocol clients already rely on; `Structured`
+/// exchanges length-prefixed, bincode-encoded `StatementResult` frames so
+/// values containing spaces or `|`, and result sets larger than the old
+/// fixed 1024-byte buffer, come through unambiguous and untruncated.
+#[derive(Clone, Copy, Debug)]
+enum ClientMode {
+    Text,
+    Structured,
+}
+
+async fn handle_client(stream: tokio::net::TcpStream, db: Arc<Database>, mode: ClientMode) {
+    match mode {
+        ClientMode::Text => handle_text_client(stream, db).await,
+        ClientMode::Structured => handle_structured_client(stream, db).await,
+    }
+}
+
+async fn handle_text_client(mut stream: tokio::net::TcpStream, db: Arc<Database>) {
     let mut buffer = [0; 1024];
 
     while let Ok(n) = stream.read(&mut buffer).await {
@@ -226,7 +1258,7 @@ async fn handle_client(mut stream: tokio::net::TcpStream, db: Arc<Mutex<Database
         }
 
         let query = String::from_utf8_lossy(&buffer[..n]).to_string();
-        let response = process_query(&query, &db);
+        let response = format_text(&execute_statement(&query, &db));
 
         if let Err(e) = stream.write_all(response.as_bytes()).await {
             eprintln!("Failed to write to stream: {}", e);
@@ -235,14 +1267,91 @@ async fn handle_client(mut stream: tokio::net::TcpStream, db: Arc<Mutex<Database
     }
 }
 
-fn process_query(query: &str, db: &Arc<Mutex<Database>>) -> String {
-    let mut db = db.lock().unwrap();
+async fn handle_structured_client(mut stream: tokio::net::TcpStream, db: Arc<Database>) {
+    loop {
+        let query_bytes = match read_frame(&mut stream).await {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return,
+            Err(e) => {
+                eprintln!("Failed to read frame: {}", e);
+                return;
+            }
+        };
+
+        let query = String::from_utf8_lossy(&query_bytes).to_string();
+        let result = execute_statement(&query, &db);
+
+        let encoded = match serialize(&result) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("Failed to encode result: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = write_frame(&mut stream, &encoded).await {
+            eprintln!("Failed to write frame: {}", e);
+            return;
+        }
+    }
+}
+
+/// Upper bound on a single frame's payload, so a bogus or hostile length
+/// prefix can't make `read_frame` allocate gigabytes on one client's say-so.
+const MAX_FRAME_LEN: usize = 64 * 1024 * 1024;
+
+/// Reads one length-prefixed frame: a big-endian `u32` byte count followed
+/// by exactly that many bytes. Returns `Ok(None)` on a clean EOF between
+/// frames (the client closed the connection) rather than an error.
+async fn read_frame(stream: &mut tokio::net::TcpStream) -> std::io::Result<Option<Vec<u8>>> {
+    let mut len_buf = [0u8; 4];
+    match stream.read_exact(&mut len_buf).await {
+        // Unlike the sync `std::io::Read::read_exact`, tokio's async version
+        // resolves to the byte count read (`io::Result<usize>`), not `()` —
+        // `Ok(_)` is the only pattern that type-checks here, not `Ok(())`.
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame length {} exceeds max of {}", len, MAX_FRAME_LEN),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+/// Writes one length-prefixed frame in the same format `read_frame` reads.
+async fn write_frame(stream: &mut tokio::net::TcpStream, payload: &[u8]) -> std::io::Result<()> {
+    let len = u32::try_from(payload.len()).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame payload of {} bytes exceeds u32::MAX", payload.len()),
+        )
+    })?;
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(payload).await?;
+    Ok(())
+}
+
+fn execute_statement(query: &str, db: &Database) -> StatementResult {
     let parts: Vec<&str> = query.split_whitespace().collect();
 
-    match parts[0].to_uppercase().as_str() {
-        "CREATE" => {
+    if parts.is_empty() {
+        return StatementResult::from_result(Err(DbError::SyntaxError(
+            "Empty query".to_string(),
+        )));
+    }
+
+    let result: Result<StatementResult, DbError> = match parts[0].to_uppercase().as_str() {
+        "CREATE" => (|| {
             if parts.len() < 4 || parts[1].to_uppercase() != "TABLE" {
-                return "Invalid CREATE TABLE syntax\n".to_string();
+                return Err(DbError::SyntaxError(
+                    "Invalid CREATE TABLE syntax".to_string(),
+                ));
             }
             let table_name = parts[2];
             let columns: Vec<Column> = parts[3..]
@@ -257,28 +1366,40 @@ fn process_query(query: &str, db: &Arc<Mutex<Database>>) -> String {
                 columns,
                 rows: Vec::new(),
             };
-            match db.create_table(table) {
-                Ok(_) => "Table created successfully\n".to_string(),
-                Err(e) => format!("Error creating table: {}\n", e),
+            db.create_table(table)?;
+            Ok(StatementResult::CreateTable)
+        })(),
+        "ALTER" => (|| {
+            if parts.len() != 7
+                || parts[1].to_uppercase() != "TABLE"
+                || parts[3].to_uppercase() != "ADD"
+                || parts[4].to_uppercase() != "COLUMN"
+            {
+                return Err(DbError::SyntaxError(
+                    "Invalid ALTER TABLE syntax".to_string(),
+                ));
             }
-        }
-        "INSERT" => {
+            let table_name = parts[2];
+            let column_name = parts[5];
+            let data_type = parts[6];
+            db.alter_table_add_column(table_name, column_name, data_type)?;
+            Ok(StatementResult::TableAltered)
+        })(),
+        "INSERT" => (|| {
             if parts.len() < 5
                 || parts[1].to_uppercase() != "INTO"
                 || parts[3].to_uppercase() != "VALUES"
             {
-                return "Invalid INSERT syntax\n".to_string();
+                return Err(DbError::SyntaxError("Invalid INSERT syntax".to_string()));
             }
             let table_name = parts[2];
             let values: Vec<String> = parts[4..].iter().map(|s| s.to_string()).collect();
-            match db.insert(table_name, values) {
-                Ok(_) => "Data inserted successfully\n".to_string(),
-                Err(e) => format!("Error inserting data: {}\n", e),
-            }
-        }
-        "SELECT" => {
+            db.insert(table_name, values)?;
+            Ok(StatementResult::Insert { count: 1 })
+        })(),
+        "SELECT" => (|| {
             if parts.len() < 4 || !parts.contains(&"FROM") {
-                return "Invalid SELECT syntax\n".to_string();
+                return Err(DbError::SyntaxError("Invalid SELECT syntax".to_string()));
             }
             let from_position = parts
                 .iter()
@@ -299,40 +1420,148 @@ fn process_query(query: &str, db: &Arc<Mutex<Database>>) -> String {
 
             let where_clause = where_position.map(|pos| parts[pos + 1..].join(" "));
 
-            match db.select(table_name, columns, where_clause.as_deref()) {
-                Ok(results) => {
-                    let mut response = String::new();
-                    for row in results {
-                        response.push_str(&row.join(" | "));
-                        response.push('\n');
-                    }
-                    response
-                }
-                Err(e) => format!("Error executing query: {}\n", e),
+            let mut results = db.select(table_name, columns, where_clause.as_deref())?;
+            let columns = if results.is_empty() {
+                Vec::new()
+            } else {
+                results.remove(0)
+            };
+            Ok(StatementResult::Select {
+                columns,
+                rows: results,
+            })
+        })(),
+        "UPDATE" => (|| {
+            if parts.len() < 6 || parts[2].to_uppercase() != "SET" || parts[4] != "=" {
+                return Err(DbError::SyntaxError("Invalid UPDATE syntax".to_string()));
+            }
+            let table_name = parts[1];
+            let column = parts[3];
+            let where_position = parts.iter().position(|&p| p.to_uppercase() == "WHERE");
+            let value = match where_position {
+                Some(pos) => parts[5..pos].join(" "),
+                None => parts[5..].join(" "),
+            };
+            let where_clause = where_position.map(|pos| parts[pos + 1..].join(" "));
+
+            let count = db.update(table_name, column, &value, where_clause.as_deref())?;
+            Ok(StatementResult::Update { count })
+        })(),
+        "DELETE" => (|| {
+            if parts.len() < 3 || parts[1].to_uppercase() != "FROM" {
+                return Err(DbError::SyntaxError("Invalid DELETE syntax".to_string()));
+            }
+            let table_name = parts[2];
+            let where_position = parts.iter().position(|&p| p.to_uppercase() == "WHERE");
+            let where_clause = where_position.map(|pos| parts[pos + 1..].join(" "));
+
+            let count = db.delete(table_name, where_clause.as_deref())?;
+            Ok(StatementResult::Delete { count })
+        })(),
+        "PREPARE" => (|| {
+            if parts.len() < 4 || parts[2].to_uppercase() != "AS" {
+                return Err(DbError::SyntaxError("Invalid PREPARE syntax".to_string()));
+            }
+            let stmt_name = parts[1].to_string();
+            let prepared = PreparedStatement::parse(&parts[3..])?;
+            db.prepared.lock().unwrap().insert(stmt_name, prepared);
+            Ok(StatementResult::Prepared)
+        })(),
+        "EXECUTE" => (|| {
+            if parts.len() < 2 {
+                return Err(DbError::SyntaxError("Invalid EXECUTE syntax".to_string()));
+            }
+            let stmt_name = parts[1];
+            let args: Vec<String> = parts[2..].iter().map(|s| s.to_string()).collect();
+
+            let prepared = db
+                .prepared
+                .lock()
+                .unwrap()
+                .get(stmt_name)
+                .cloned()
+                .ok_or_else(|| {
+                    DbError::SyntaxError(format!(
+                        "prepared statement '{}' not found",
+                        stmt_name
+                    ))
+                })?;
+
+            if args.len() != prepared.param_count {
+                return Err(DbError::ArityMismatch(format!(
+                    "expected {} argument(s), got {}",
+                    prepared.param_count,
+                    args.len()
+                )));
+            }
+
+            execute_prepared(&prepared, &args, db)
+        })(),
+        _ => Err(DbError::SyntaxError("Invalid query".to_string())),
+    };
+
+    StatementResult::from_result(result)
+}
+
+/// Renders a `StatementResult` the way the legacy text protocol always
+/// has: `CREATE`/`ALTER`/`PREPARE` get a fixed success line, `SELECT`
+/// folds its column header back into the `col | col` row format,
+/// UPDATE/DELETE counts render as `N row(s) updated`/`N row(s) deleted`,
+/// and errors render as `ERROR <code>: <message>`.
+fn format_text(result: &StatementResult) -> String {
+    match result {
+        StatementResult::CreateTable => "Table created successfully\n".to_string(),
+        StatementResult::TableAltered => "Table altered successfully\n".to_string(),
+        StatementResult::Insert { .. } => "Data inserted successfully\n".to_string(),
+        StatementResult::Select { columns, rows } => {
+            let mut response = String::new();
+            response.push_str(&columns.join(" | "));
+            response.push('\n');
+            for row in rows {
+                response.push_str(&row.join(" | "));
+                response.push('\n');
             }
+            response
         }
-        _ => "Invalid query\n".to_string(),
+        StatementResult::Update { count } => format!("{} row(s) updated\n", count),
+        StatementResult::Delete { count } => format!("{} row(s) deleted\n", count),
+        StatementResult::Prepared => "Statement prepared successfully\n".to_string(),
+        StatementResult::Error { code, message } => format!("ERROR {}: {}\n", code, message),
     }
 }
 
 #[cfg(test)]
 mod tests;
 
+/// Accepts connections from `listener` forever, spawning each one onto
+/// `handle_client` under the given `mode`.
+async fn accept_loop(listener: TcpListener, db: Arc<Database>, mode: ClientMode) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let db_clone = Arc::clone(&db);
+                tokio::spawn(async move {
+                    handle_client(stream, db_clone, mode).await;
+                });
+            }
+            Err(e) => eprintln!("Failed to accept connection: {}", e),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind("127.0.0.1:8080").await?;
-    println!("Listening on 127.0.0.1:8080");
+    let text_listener = TcpListener::bind("127.0.0.1:8080").await?;
+    let structured_listener = TcpListener::bind("127.0.0.1:8081").await?;
+    println!("Listening on 127.0.0.1:8080 (text) and 127.0.0.1:8081 (structured)");
 
-    let db = Arc::new(Mutex::new(Database::new("simple_db.bin")));
+    let db = Arc::new(Database::new("simple_db.bin"));
 
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let db_clone = Arc::clone(&db);
+    let text_db = Arc::clone(&db);
+    tokio::spawn(accept_loop(text_listener, text_db, ClientMode::Text));
+    accept_loop(structured_listener, db, ClientMode::Structured).await;
 
-        tokio::spawn(async move {
-            handle_client(stream, db_clone).await;
-        });
-    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -341,18 +1570,28 @@ pub fn start_test_server(db_file: &str) -> std::io::Result<()> {
 
     let rt = Runtime::new()?;
     rt.block_on(async {
-        let listener = TcpListener::bind("127.0.0.1:8080").await?;
-        println!("Test server listening on 127.0.0.1:8080");
+        let text_listener = TcpListener::bind("127.0.0.1:8080").await?;
+        let structured_listener = TcpListener::bind("127.0.0.1:8081").await?;
+        println!("Test server listening on 127.0.0.1:8080 (text) and 127.0.0.1:8081 (structured)");
 
-        let db = Arc::new(Mutex::new(Database::new(db_file)));
+        let db = Arc::new(Database::new(db_file));
+        let text_db = Arc::clone(&db);
 
         loop {
-            let (stream, _) = listener.accept().await?;
-            let db_clone = Arc::clone(&db);
-
-            tokio::spawn(async move {
-                handle_client(stream, db_clone).await;
-            });
+            tokio::select! {
+                Ok((stream, _)) = text_listener.accept() => {
+                    let db_clone = Arc::clone(&text_db);
+                    tokio::spawn(async move {
+                        handle_client(stream, db_clone, ClientMode::Text).await;
+                    });
+                }
+                Ok((stream, _)) = structured_listener.accept() => {
+                    let db_clone = Arc::clone(&db);
+                    tokio::spawn(async move {
+                        handle_client(stream, db_clone, ClientMode::Structured).await;
+                    });
+                }
+            }
         }
     })
 }